@@ -4,7 +4,9 @@
 
 use crate::ops::{ok, OpResult};
 use crate::project;
+use crate::project::AbsPathBuf;
 use crate::VERSION_BUILD_REV;
+use std::io;
 
 /// See the documentation for lorri::cli::Command::Info for more
 /// details.
@@ -13,9 +15,22 @@ pub fn main(project: &project::Project) -> OpResult {
     println!("Lorri Project Configuration");
     println!();
 
-    println!(" project root: {}", project.project_root.display());
+    println!(" project root: {}", project.project_root);
 
-    println!("   expression: {}", project.expression().display());
+    println!("   expression: {}", project.expression());
+
+    println!("  config home: {}", display_dir(project.config_home()));
+    println!("   cache home: {}", display_dir(project.cache_home()));
+    println!("    data home: {}", display_dir(project.data_home()));
 
     ok()
 }
+
+/// Render a per-project XDG directory for display, surfacing any IO
+/// error inline rather than aborting the whole `info` op over it.
+fn display_dir(dir: Result<AbsPathBuf, io::Error>) -> String {
+    match dir {
+        Ok(path) => path.to_string(),
+        Err(e) => format!("<unavailable: {}>", e),
+    }
+}
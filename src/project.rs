@@ -1,25 +1,120 @@
 //! Project-level functions, like preferred configuration
 //! and on-disk locations.
 
-use locate_file;
 use locate_file::FileLocationError;
+use std::convert::TryFrom;
+use std::env;
+use std::fmt;
 use std::io;
 use std::os::unix::ffi::OsStrExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// A `PathBuf` that is guaranteed to be absolute.
+///
+/// `Project` relies on its paths being absolute (the md5 `hash()`
+/// and the GC root layout both silently depend on it), so we make
+/// that invariant explicit and type-checked instead of relying on
+/// callers to pass the right thing.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AbsPathBuf(PathBuf);
+
+impl AbsPathBuf {
+    /// Borrow this path as an `AbsPath`.
+    pub fn as_path(&self) -> AbsPath<'_> {
+        AbsPath(&self.0)
+    }
+
+    /// The underlying `Path`.
+    pub fn as_ref_path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Join a (possibly relative) path onto this absolute path,
+    /// yielding another absolute path.
+    pub fn join<P: AsRef<Path>>(&self, other: P) -> AbsPathBuf {
+        AbsPathBuf(self.0.join(other))
+    }
+
+    /// The parent directory, if this path isn't the filesystem root.
+    pub fn parent(&self) -> Option<AbsPath<'_>> {
+        self.0.parent().map(AbsPath)
+    }
+}
+
+impl TryFrom<PathBuf> for AbsPathBuf {
+    type Error = PathBuf;
+
+    /// Fails if `path` is not absolute.
+    fn try_from(path: PathBuf) -> Result<Self, Self::Error> {
+        if path.is_absolute() {
+            Ok(AbsPathBuf(path))
+        } else {
+            Err(path)
+        }
+    }
+}
+
+impl fmt::Display for AbsPathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
+
+/// A borrowed, absolute `Path`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AbsPath<'a>(&'a Path);
+
+impl<'a> AbsPath<'a> {
+    /// The underlying `Path`.
+    pub fn as_path(&self) -> &'a Path {
+        self.0
+    }
+
+    /// Join a (possibly relative) path onto this absolute path,
+    /// yielding another absolute path.
+    pub fn join<P: AsRef<Path>>(&self, other: P) -> AbsPathBuf {
+        AbsPathBuf(self.0.join(other))
+    }
+
+    /// The parent directory, if this path isn't the filesystem root.
+    pub fn parent(&self) -> Option<AbsPath<'a>> {
+        self.0.parent().map(AbsPath)
+    }
+
+    /// Clone into an owned `AbsPathBuf`.
+    pub fn to_path_buf(&self) -> AbsPathBuf {
+        AbsPathBuf(self.0.to_path_buf())
+    }
+}
+
+impl<'a> fmt::Display for AbsPath<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
 
 /// A specific project which we are operating on
 #[derive(Debug)]
 pub struct Project {
     /// The file on disk to the shell.nix
-    pub nix_file: PathBuf,
+    pub nix_file: AbsPathBuf,
 
     // TODO: completely superfluous, lorri only needs
     // to know about the nix file
-    /// The root directory containing the project's files
-    pub project_root: PathBuf,
+    /// The logical root directory containing the project's files, as
+    /// given by the user or the cwd. Used for display purposes (e.g.
+    /// the `info` op) so users see the path they expect.
+    pub project_root: AbsPathBuf,
+
+    /// The canonicalized (symlinks resolved) `project_root`, used to
+    /// key all on-disk state (see `hash()`), so that a project reached
+    /// through a symlink maps to the same GC roots and caches as the
+    /// real path. Only symlinks are resolved: a bind mount or a
+    /// differently-cased path still yields a different path here.
+    project_root_physical: AbsPathBuf,
 
     /// Directory, in which garbage collection roots will be stored
-    gc_root: PathBuf,
+    gc_root: AbsPathBuf,
 }
 
 /// Error conditions encountered when finding and loading a Lorri
@@ -29,6 +124,10 @@ pub enum ProjectLoadError {
     /// The shell.nix was not found in a directory search.
     ConfigNotFound,
 
+    /// A path that was required to be absolute (e.g. the `shell.nix`
+    /// or the gc root directory) was passed as relative.
+    NotAbsolute(PathBuf),
+
     /// An IO error occured while finding the project
     Io(io::Error),
 }
@@ -42,12 +141,35 @@ impl From<FileLocationError> for ProjectLoadError {
     }
 }
 
+/// The project's supported entry points, in priority order, when no
+/// override is given. A project-local `.lorri.nix` takes precedence
+/// over the conventional `shell.nix`, which in turn takes precedence
+/// over `default.nix`.
+const DEFAULT_ENTRY_POINTS: &[&str] = &[".lorri.nix", "shell.nix", "default.nix"];
+
+/// Environment variable used to override the marker filename(s)
+/// `from_cwd` searches for, taking precedence over
+/// `DEFAULT_ENTRY_POINTS`.
+const NIX_FILE_ENV: &str = "LORRI_NIX_FILE";
+
 impl Project {
     /// Load a Project based on the current working directory,
-    /// locating a `shell.nix` configuration file in the current
-    /// directory.
+    /// walking up from the cwd to locate one of the project's
+    /// supported entry points (see `find_root`). The candidate
+    /// filename can be overridden by setting `$LORRI_NIX_FILE`.
+    ///
+    /// The cwd is not canonicalized again here; `env::current_dir()`
+    /// reports it as the OS does (on Unix, `getcwd`, which already has
+    /// symlinks resolved), so `project_root` does not reflect symlinks
+    /// the user cd'd through. `Project::load` separately resolves
+    /// `project_root_physical` for on-disk state keying.
     pub fn from_cwd() -> Result<Project, ProjectLoadError> {
-        let shell_nix = locate_file::in_cwd("shell.nix")?;
+        let cwd = env::current_dir().map_err(ProjectLoadError::Io)?;
+
+        let entry_points = Project::candidate_entry_points();
+        let entry_points: Vec<&str> = entry_points.iter().map(String::as_str).collect();
+
+        let shell_nix = Project::find_root(&cwd, &entry_points)?;
 
         Project::load(
             shell_nix,
@@ -59,47 +181,381 @@ impl Project {
         )
     }
 
+    /// Load a Project from an explicit nix file, bypassing entry
+    /// point discovery entirely. Lets users with non-standard layouts
+    /// or several Nix shells in one repo point lorri at the right
+    /// expression directly.
+    pub fn from_nix_file(nix_file: PathBuf) -> Result<Project, ProjectLoadError> {
+        Project::load(
+            nix_file,
+            ::constants::Paths::initialize()
+                // TODO: don’t initialize in here
+                .expect("Error: cannot initialize lorri paths")
+                .gc_root_dir()
+                .to_owned(),
+        )
+    }
+
+    /// The entry point filenames `from_cwd` searches for: just
+    /// `$LORRI_NIX_FILE` if it's set, otherwise `DEFAULT_ENTRY_POINTS`.
+    fn candidate_entry_points() -> Vec<String> {
+        match env::var(NIX_FILE_ENV) {
+            Ok(name) => vec![name],
+            Err(_) => DEFAULT_ENTRY_POINTS.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// Walk `start`'s ancestors to find the first of `filenames`
+    /// present in a directory, following this priority order:
+    ///
+    /// 1. the top-most ancestor inside the enclosing git repository
+    ///    that contains one of `filenames`;
+    /// 2. the git repository root, if it holds one of `filenames`;
+    /// 3. the top-most ancestor (up to the filesystem root) that
+    ///    contains one of `filenames`, when `start` is not inside a
+    ///    git repo;
+    /// 4. `start` itself, as a last resort.
+    ///
+    /// Within a single directory, `filenames` are tried in the order
+    /// given. This never silently gives up: it either returns a
+    /// definite path or a `ConfigNotFound` when no ancestor (including
+    /// `start` itself) qualifies.
+    fn find_root(start: &Path, filenames: &[&str]) -> Result<PathBuf, ProjectLoadError> {
+        let git_root = Project::find_git_root(start);
+
+        // Ancestors of `start`, bounded above by the git root (or, if
+        // there is none, the filesystem root), ordered top-most first
+        // so we pick the highest ancestor that qualifies.
+        let mut candidates: Vec<&Path> = start
+            .ancestors()
+            .take_while(|dir| match &git_root {
+                Some(root) => dir.starts_with(root),
+                None => true,
+            })
+            .collect();
+        candidates.reverse();
+
+        for dir in candidates {
+            for filename in filenames {
+                if dir.join(filename).is_file() {
+                    return Ok(dir.join(filename));
+                }
+            }
+        }
+
+        // Nothing found while walking up, including at `start` itself.
+        Err(ProjectLoadError::ConfigNotFound)
+    }
+
+    /// Walk `start`'s ancestors looking for the directory that
+    /// contains the enclosing `.git`, stopping as soon as we leave
+    /// that worktree. Returns `None` if `start` is not inside a git
+    /// repository.
+    fn find_git_root(start: &Path) -> Option<PathBuf> {
+        start
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .map(|dir| dir.to_path_buf())
+    }
+
     /// Given an absolute path to a shell.nix,
     /// construct a Project and a ProjectConfig.
+    ///
+    /// Returns `ProjectLoadError::NotAbsolute` if either `nix_file`
+    /// or `gc_root` is not absolute.
     pub fn load(nix_file: PathBuf, gc_root: PathBuf) -> Result<Project, ProjectLoadError> {
+        let nix_file = AbsPathBuf::try_from(nix_file).map_err(ProjectLoadError::NotAbsolute)?;
+        let gc_root = AbsPathBuf::try_from(gc_root).map_err(ProjectLoadError::NotAbsolute)?;
+
         let project_root = nix_file
+            .as_path()
             .parent()
             // only None if `shell_nix` is "/"
-            .unwrap();
+            .unwrap()
+            .to_path_buf();
+
+        let project_root_physical = match project_root.as_ref_path().canonicalize() {
+            Ok(physical) => AbsPathBuf::try_from(physical)
+                .expect("canonicalize() always returns an absolute path"),
+            Err(e) => {
+                warn!(
+                    "could not canonicalize project root {:?}, using it as-is: {}",
+                    project_root, e
+                );
+                project_root.clone()
+            }
+        };
 
         Ok(Project {
-            project_root: project_root.to_path_buf(),
-            nix_file: nix_file.clone(),
+            project_root,
+            project_root_physical,
+            nix_file,
             gc_root,
         })
     }
 
     /// Absolute path to the the project's primary entry points
     /// expression
-    pub fn expression(&self) -> PathBuf {
+    pub fn expression(&self) -> AbsPathBuf {
         self.nix_file.clone()
     }
 
     /// Absolute path to the projects' gc root directory, for pinning
     /// build and evaluation products
-    pub fn gc_root_path(&self) -> Result<PathBuf, std::io::Error> {
+    pub fn gc_root_path(&self) -> Result<AbsPathBuf, std::io::Error> {
         // TODO: use a hash of the project’s abolute path here
         // to avoid collisions
         let path = self.gc_root.join(self.hash()).join("gc_root");
 
-        if !path.is_dir() {
+        if !path.as_ref_path().is_dir() {
             debug!("Creating all directories for GC roots in {:?}", path);
-            std::fs::create_dir_all(&path)?;
+            std::fs::create_dir_all(path.as_ref_path())?;
         }
 
-        Ok(path.to_path_buf())
+        Ok(path)
+    }
+
+    /// Absolute path to the project's configuration directory, under
+    /// `$XDG_CONFIG_HOME` (or `~/.config`), namespaced by `hash()`.
+    pub fn config_home(&self) -> Result<AbsPathBuf, std::io::Error> {
+        self.xdg_home("XDG_CONFIG_HOME", ".config")
     }
 
-    /// Generate a "unique" ID for this project based on its absolute path
+    /// Absolute path to the project's cache directory, under
+    /// `$XDG_CACHE_HOME` (or `~/.cache`), namespaced by `hash()`.
+    pub fn cache_home(&self) -> Result<AbsPathBuf, std::io::Error> {
+        self.xdg_home("XDG_CACHE_HOME", ".cache")
+    }
+
+    /// Absolute path to the project's data directory, under
+    /// `$XDG_DATA_HOME` (or `~/.local/share`), namespaced by
+    /// `hash()`.
+    pub fn data_home(&self) -> Result<AbsPathBuf, std::io::Error> {
+        self.xdg_home("XDG_DATA_HOME", ".local/share")
+    }
+
+    /// Resolve `$<xdg_var>` (falling back to `~/<home_fallback>`),
+    /// namespace it by this project's `hash()`, and discover-and-assume
+    /// the resulting directory: create it lazily if it doesn't exist
+    /// yet, the same way `gc_root_path()` does.
+    fn xdg_home(&self, xdg_var: &str, home_fallback: &str) -> Result<AbsPathBuf, std::io::Error> {
+        let base = env::var_os(xdg_var)
+            .map(PathBuf::from)
+            .or_else(|| dirs::home_dir().map(|home| home.join(home_fallback)))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("could not determine {} or $HOME", xdg_var),
+                )
+            })?;
+        let base = AbsPathBuf::try_from(base).map_err(|path| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} resolved to a relative path: {:?}", xdg_var, path),
+            )
+        })?;
+
+        let path = base.join("lorri").join(self.hash());
+
+        if !path.as_ref_path().is_dir() {
+            debug!("Creating all directories for {:?} in {:?}", xdg_var, path);
+            std::fs::create_dir_all(path.as_ref_path())?;
+        }
+
+        Ok(path)
+    }
+
+    /// Generate a "unique" ID for this project based on its canonical,
+    /// physical path, so that symlink aliases of a project share the
+    /// same GC roots and caches. Bind mounts and differently-cased
+    /// paths are not unified, as canonicalization leaves them as-is.
     pub fn hash(&self) -> String {
         format!(
             "{:x}",
-            md5::compute(self.project_root.as_os_str().as_bytes())
+            md5::compute(
+                self.project_root_physical
+                    .as_ref_path()
+                    .as_os_str()
+                    .as_bytes()
+            )
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn touch(path: &Path) {
+        fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn abs_path_buf_rejects_relative_paths() {
+        assert!(AbsPathBuf::try_from(PathBuf::from("relative/path")).is_err());
+        assert!(AbsPathBuf::try_from(PathBuf::from("/absolute/path")).is_ok());
+    }
+
+    #[test]
+    fn find_root_walks_up_to_ancestor_without_git() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        let sub = root.join("a").join("b");
+        fs::create_dir_all(&sub).unwrap();
+        touch(&root.join("shell.nix"));
+
+        let found = Project::find_root(&sub, &["shell.nix"]).unwrap();
+        assert_eq!(found, root.join("shell.nix"));
+    }
+
+    #[test]
+    fn find_root_prefers_topmost_ancestor_inside_git_repo() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let sub = root.join("a").join("b");
+        fs::create_dir_all(&sub).unwrap();
+        touch(&root.join("shell.nix"));
+        touch(&root.join("a").join("shell.nix"));
+
+        let found = Project::find_root(&sub, &["shell.nix"]).unwrap();
+        assert_eq!(found, root.join("shell.nix"));
+    }
+
+    #[test]
+    fn find_root_prefers_dot_lorri_nix_over_shell_nix_over_default_nix() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        touch(&root.join("shell.nix"));
+        touch(&root.join("default.nix"));
+        touch(&root.join(".lorri.nix"));
+
+        let found = Project::find_root(&root, DEFAULT_ENTRY_POINTS).unwrap();
+        assert_eq!(found, root.join(".lorri.nix"));
+
+        // with .lorri.nix out of the picture, shell.nix wins next
+        fs::remove_file(root.join(".lorri.nix")).unwrap();
+        let found = Project::find_root(&root, DEFAULT_ENTRY_POINTS).unwrap();
+        assert_eq!(found, root.join("shell.nix"));
+    }
+
+    #[test]
+    fn env_override_replaces_default_entry_points_and_wins_in_find_root() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        touch(&root.join("shell.nix"));
+        touch(&root.join("my-shell.nix"));
+
+        env::set_var(NIX_FILE_ENV, "my-shell.nix");
+        let entry_points = Project::candidate_entry_points();
+        env::remove_var(NIX_FILE_ENV);
+        assert_eq!(entry_points, vec!["my-shell.nix".to_string()]);
+
+        let entry_points: Vec<&str> = entry_points.iter().map(String::as_str).collect();
+        let found = Project::find_root(&root, &entry_points).unwrap();
+        assert_eq!(found, root.join("my-shell.nix"));
+    }
+
+    #[test]
+    fn from_nix_file_round_trips_through_load() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        let nix_file = root.join("my-shell.nix");
+        touch(&nix_file);
+
+        let via_load = Project::load(
+            nix_file.clone(),
+            ::constants::Paths::initialize()
+                .unwrap()
+                .gc_root_dir()
+                .to_owned(),
         )
+        .unwrap();
+        let via_from_nix_file = Project::from_nix_file(nix_file.clone()).unwrap();
+
+        assert_eq!(via_load.nix_file, via_from_nix_file.nix_file);
+        assert_eq!(via_from_nix_file.expression().as_ref_path(), nix_file);
+    }
+
+    #[test]
+    fn find_root_does_not_cross_the_git_boundary() {
+        let tmp = tempdir().unwrap();
+        let outer = tmp.path().canonicalize().unwrap();
+        touch(&outer.join("shell.nix"));
+        let repo = outer.join("repo");
+        fs::create_dir_all(repo.join(".git")).unwrap();
+        let sub = repo.join("a");
+        fs::create_dir_all(&sub).unwrap();
+
+        let result = Project::find_root(&sub, &["shell.nix"]);
+        assert!(matches!(result, Err(ProjectLoadError::ConfigNotFound)));
+    }
+
+    #[test]
+    fn find_root_returns_config_not_found_when_nothing_matches() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path().canonicalize().unwrap();
+
+        let result = Project::find_root(&dir, &["shell.nix"]);
+        assert!(matches!(result, Err(ProjectLoadError::ConfigNotFound)));
+    }
+
+    #[test]
+    fn find_git_root_locates_enclosing_worktree() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        let sub = root.join("a").join("b");
+        fs::create_dir_all(&sub).unwrap();
+
+        assert_eq!(Project::find_git_root(&sub), Some(root));
+    }
+
+    #[test]
+    fn find_git_root_is_none_outside_a_repo() {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path().canonicalize().unwrap();
+
+        assert_eq!(Project::find_git_root(&dir), None);
+    }
+
+    #[test]
+    fn hash_is_stable_across_a_symlink_alias() {
+        let tmp = tempdir().unwrap();
+        let tmp_root = tmp.path().canonicalize().unwrap();
+        let real_root = tmp_root.join("real");
+        fs::create_dir_all(&real_root).unwrap();
+        touch(&real_root.join("shell.nix"));
+
+        let alias_root = tmp_root.join("alias");
+        std::os::unix::fs::symlink(&real_root, &alias_root).unwrap();
+
+        let via_real = Project::load(real_root.join("shell.nix"), tmp_root.join("gc1")).unwrap();
+        let via_alias = Project::load(alias_root.join("shell.nix"), tmp_root.join("gc2")).unwrap();
+
+        assert_eq!(via_real.hash(), via_alias.hash());
+    }
+
+    #[test]
+    fn xdg_home_uses_env_var_and_namespaces_by_hash() {
+        let tmp = tempdir().unwrap();
+        let root = tmp.path().canonicalize().unwrap();
+        let nix_file = root.join("shell.nix");
+        touch(&nix_file);
+
+        let project = Project::load(nix_file, root.join("gc_roots")).unwrap();
+
+        let xdg_home = root.join("xdg-config");
+        env::set_var("XDG_CONFIG_HOME", &xdg_home);
+        let config_home = project.config_home().unwrap();
+        env::remove_var("XDG_CONFIG_HOME");
+
+        assert_eq!(
+            config_home.as_ref_path(),
+            xdg_home.join("lorri").join(project.hash())
+        );
+        assert!(config_home.as_ref_path().is_dir());
     }
 }